@@ -0,0 +1,238 @@
+//! An insertion-order-preserving `MultiMap` variant, analogous to how
+//! `IndexMap` relates to `HashMap`.
+
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+/// A multimap that preserves key insertion order, backed by a `Vec` of
+/// `(K, Vec<V>)` buckets plus a hash index mapping each key to its
+/// position in that `Vec`.
+///
+/// Unlike `MultiMap`, iteration order matches first-insertion order
+/// regardless of hashing, until [`swap_remove`](IndexMultiMap::swap_remove)
+/// or [`sort_keys`](IndexMultiMap::sort_keys) reorders the entries. Positional
+/// operations like
+/// [`get_index`](IndexMultiMap::get_index) and
+/// [`swap_remove`](IndexMultiMap::swap_remove) are available. `insert`,
+/// `get`, and `remove` keep the same duplicate-friendly, append-to-bucket
+/// semantics as `MultiMap`.
+pub struct IndexMultiMap<K, V, S = RandomState> {
+    entries: Vec<(K, Vec<V>)>,
+    index: HashMap<K, usize, S>,
+}
+
+impl<K: Hash + Eq + Clone, V> Default for IndexMultiMap<K, V> {
+    fn default() -> IndexMultiMap<K, V> {
+        IndexMultiMap::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> IndexMultiMap<K, V> {
+    /// Construct a new, empty `IndexMultiMap`.
+    pub fn new() -> IndexMultiMap<K, V> {
+        IndexMultiMap {
+            entries: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    /// Construct a new `IndexMultiMap` with space reserved for `capacity`
+    /// keys.
+    pub fn with_capacity(capacity: usize) -> IndexMultiMap<K, V> {
+        IndexMultiMap {
+            entries: Vec::with_capacity(capacity),
+            index: HashMap::with_capacity(capacity),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V, S: BuildHasher> IndexMultiMap<K, V, S> {
+    /// Construct a new, empty `IndexMultiMap` that will use the given
+    /// hash builder to hash keys.
+    pub fn with_hasher(hash_builder: S) -> IndexMultiMap<K, V, S> {
+        IndexMultiMap {
+            entries: Vec::new(),
+            index: HashMap::with_hasher(hash_builder),
+        }
+    }
+
+    /// Construct a new `IndexMultiMap` with space reserved for `capacity`
+    /// keys, using the given hash builder to hash keys.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> IndexMultiMap<K, V, S> {
+        IndexMultiMap {
+            entries: Vec::with_capacity(capacity),
+            index: HashMap::with_capacity_and_hasher(capacity, hash_builder),
+        }
+    }
+
+    /// Returns the number of keys in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the map contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Return true if the map contains a value for a specified key.
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.index.contains_key(k)
+    }
+
+    /// Retrieves the bucket of values for the given key, if present.
+    pub fn get(&self, k: &K) -> Option<&Vec<V>> {
+        self.index.get(k).map(|&i| &self.entries[i].1)
+    }
+
+    /// Retrieves the (mutable) bucket of values for the given key, if
+    /// present.
+    pub fn get_mut(&mut self, k: &K) -> Option<&mut Vec<V>> {
+        match self.index.get(k) {
+            Some(&i) => Some(&mut self.entries[i].1),
+            None => None,
+        }
+    }
+
+    /// Returns the key-bucket pair at position `i`, in insertion order, if
+    /// within bounds.
+    pub fn get_index(&self, i: usize) -> Option<(&K, &Vec<V>)> {
+        self.entries.get(i).map(|(k, v)| (k, v))
+    }
+
+    /// Inserts the specified key-value pair into the multimap.
+    ///
+    /// Duplicate key-value pairs are allowed. If the key is already
+    /// present, `v` is appended to its existing bucket; otherwise a new
+    /// bucket is appended at the end, preserving insertion order.
+    pub fn insert(&mut self, k: K, v: V) {
+        match self.index.get(&k) {
+            Some(&i) => self.entries[i].1.push(v),
+            None => {
+                self.index.insert(k.clone(), self.entries.len());
+                self.entries.push((k, vec![v]));
+            }
+        }
+    }
+
+    /// Removes key and its associated values from the map, shifting later
+    /// entries down by one to preserve insertion order.
+    pub fn remove(&mut self, k: &K) -> bool {
+        match self.index.remove(k) {
+            Some(i) => {
+                self.entries.remove(i);
+                for (_, pos) in self.index.iter_mut() {
+                    if *pos > i {
+                        *pos -= 1;
+                    }
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes key and its associated values from the map in O(1) by
+    /// moving the last bucket into the hole left behind, without
+    /// preserving insertion order.
+    pub fn swap_remove(&mut self, k: &K) -> bool {
+        match self.index.remove(k) {
+            Some(i) => {
+                self.entries.swap_remove(i);
+                if i < self.entries.len() {
+                    let moved_key = self.entries[i].0.clone();
+                    self.index.insert(moved_key, i);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sorts the map's entries by key, updating the index to match.
+    pub fn sort_keys(&mut self) where K: Ord {
+        self.entries.sort_by(|a, b| a.0.cmp(&b.0));
+        for (i, (k, _)) in self.entries.iter().enumerate() {
+            self.index.insert(k.clone(), i);
+        }
+    }
+
+    /// An iterator visiting all key-bucket pairs in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &Vec<V>)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    /// An iterator visiting all keys in insertion order.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.iter().map(|(k, _)| k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IndexMultiMap;
+    use crate::test_support::FnvHasher;
+    use std::hash::BuildHasherDefault;
+
+    fn create_test_map() -> IndexMultiMap<usize, usize> {
+        let mut map: IndexMultiMap<usize, usize> = IndexMultiMap::new();
+        map.insert(2, 1);
+        map.insert(1, 3);
+        map.insert(1, 5);
+        map
+    }
+
+    #[test]
+    fn test_insertion_order() {
+        let map = create_test_map();
+        let keys: Vec<&usize> = map.keys().collect();
+        assert_eq!(keys, vec![&2, &1]);
+        assert_eq!(map.get(&1), Some(&vec![3, 5]));
+    }
+
+    #[test]
+    fn test_get_index() {
+        let map = create_test_map();
+        assert_eq!(map.get_index(0), Some((&2, &vec![1])));
+        assert_eq!(map.get_index(1), Some((&1, &vec![3, 5])));
+        assert_eq!(map.get_index(2), None);
+    }
+
+    #[test]
+    fn test_remove_preserves_order() {
+        let mut map = create_test_map();
+        map.insert(3, 9);
+        assert!(map.remove(&1));
+        let keys: Vec<&usize> = map.keys().collect();
+        assert_eq!(keys, vec![&2, &3]);
+        assert!(!map.contains_key(&1));
+    }
+
+    #[test]
+    fn test_swap_remove() {
+        let mut map = create_test_map();
+        map.insert(3, 9);
+        assert!(map.swap_remove(&2));
+        assert_eq!(map.get_index(0), Some((&3, &vec![9])));
+        assert!(!map.contains_key(&2));
+    }
+
+    #[test]
+    fn test_sort_keys() {
+        let mut map = create_test_map();
+        map.sort_keys();
+        let keys: Vec<&usize> = map.keys().collect();
+        assert_eq!(keys, vec![&1, &2]);
+        assert_eq!(map.get_index(0), Some((&1, &vec![3, 5])));
+    }
+
+    #[test]
+    fn test_alternate_build_hasher() {
+        let mut map: IndexMultiMap<usize, usize, BuildHasherDefault<FnvHasher>> =
+            IndexMultiMap::with_hasher(BuildHasherDefault::default());
+        map.insert(1, 3);
+        map.insert(1, 5);
+        assert_eq!(map.get(&1), Some(&vec![3, 5]));
+    }
+}
@@ -0,0 +1,155 @@
+//! Iterator types for `MultiMap`, replacing the old `as_vec` hack with
+//! lazy traversal over the underlying `HashMap<K, Vec<V>>`.
+
+use std::collections::hash_map;
+use std::slice;
+
+/// An iterator over the entries of a `MultiMap`, yielding `(&K, &Vec<V>)`.
+///
+/// Created by [`MultiMap::iter`](super::MultiMap::iter).
+pub struct Iter<'a, K: 'a, V: 'a> {
+    pub(crate) inner: hash_map::Iter<'a, K, Vec<V>>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a Vec<V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// A mutable iterator over the entries of a `MultiMap`, yielding
+/// `(&K, &mut Vec<V>)`.
+///
+/// Created by [`MultiMap::iter_mut`](super::MultiMap::iter_mut).
+pub struct IterMut<'a, K: 'a, V: 'a> {
+    pub(crate) inner: hash_map::IterMut<'a, K, Vec<V>>,
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut Vec<V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// An owning iterator over the entries of a `MultiMap`, yielding
+/// `(K, Vec<V>)`.
+///
+/// Created by the `IntoIterator` implementation for `MultiMap`.
+pub struct IntoIter<K, V> {
+    pub(crate) inner: hash_map::IntoIter<K, Vec<V>>,
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, Vec<V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// An iterator over the keys of a `MultiMap`.
+///
+/// Created by [`MultiMap::keys`](super::MultiMap::keys).
+pub struct Keys<'a, K: 'a, V: 'a> {
+    pub(crate) inner: hash_map::Keys<'a, K, Vec<V>>,
+}
+
+impl<'a, K, V> Iterator for Keys<'a, K, V> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// An iterator over the value buckets of a `MultiMap`.
+///
+/// Created by [`MultiMap::values`](super::MultiMap::values).
+pub struct Values<'a, K: 'a, V: 'a> {
+    pub(crate) inner: hash_map::Values<'a, K, Vec<V>>,
+}
+
+impl<'a, K, V> Iterator for Values<'a, K, V> {
+    type Item = &'a Vec<V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// A draining iterator over the entries of a `MultiMap`, yielding
+/// `(K, Vec<V>)` and leaving the map empty.
+///
+/// Created by [`MultiMap::drain`](super::MultiMap::drain).
+pub struct Drain<'a, K: 'a, V: 'a> {
+    pub(crate) inner: hash_map::Drain<'a, K, Vec<V>>,
+}
+
+impl<'a, K, V> Iterator for Drain<'a, K, V> {
+    type Item = (K, Vec<V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// A flattening iterator yielding one `(&K, &V)` pair per stored
+/// key-value pair, the lazy replacement for the old `as_vec` hack.
+///
+/// Holds the inner `HashMap` iterator plus the current key and a slice
+/// iterator over its bucket, advancing to the next key once the current
+/// bucket is exhausted.
+///
+/// Created by [`MultiMap::iter_flat`](super::MultiMap::iter_flat).
+pub struct IterFlat<'a, K: 'a, V: 'a> {
+    pub(crate) inner: hash_map::Iter<'a, K, Vec<V>>,
+    pub(crate) current: Option<(&'a K, slice::Iter<'a, V>)>,
+}
+
+impl<'a, K, V> Iterator for IterFlat<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, values)) = &mut self.current {
+                if let Some(value) = values.next() {
+                    return Some((*key, value));
+                }
+                self.current = None;
+            }
+
+            match self.inner.next() {
+                Some((key, values)) => self.current = Some((key, values.iter())),
+                None => return None,
+            }
+        }
+    }
+}
@@ -1,67 +1,158 @@
 //! This crate provides the `MultiMap` type, a convenient wrapper around
 //! HashMaps with multiple values per key
 
-#![crate_id="multimap#0.0.1"]
-#![crate_type="rlib"]
-#![crate_type="dylib"]
-#![warn(unnecessary_qualification, non_uppercase_statics,
-        variant_size_difference, managed_heap_memory, unnecessary_typecast,
-        missing_doc, unused_result)]
-
-use std::collections::{Collection, HashMap, Mutable};
-use std::default::Default;
-use std::fmt::Show;
+#![warn(missing_docs)]
+
+mod indexed;
+mod iter;
+mod persistent;
+
+use std::collections::HashMap;
+use std::collections::hash_map;
+use std::collections::hash_map::RandomState;
 use std::fmt;
-use std::hash::Hash;
-use std::iter::Repeat;
+use std::hash::{BuildHasher, Hash};
+
+pub use indexed::IndexMultiMap;
+pub use iter::{Drain, IntoIter, Iter, IterFlat, IterMut, Keys, Values};
+pub use persistent::PersistentMultiMap;
 
 /// A map containing multiple values per key by providing
 /// a convenient wrapper around HashMap<K, Vec<V>>.
 ///
 /// This multimap allows duplicate key-value pairs.
 ///
+/// Like `HashMap`, it is generic over a `BuildHasher` `S`, defaulting to
+/// `RandomState`. Swap in a faster non-cryptographic hasher (e.g. FNV) for
+/// small integer keys, or a deterministic one for reproducible iteration
+/// order in tests, via [`MultiMap::with_hasher`].
+///
 /// ```rust
 /// # use multimap::MultiMap;
 /// let mut data = MultiMap::new();
 /// data.insert(1, 4);
 /// data.insert(1, 8);
 /// ```
-pub struct MultiMap<K, V> {
-     data: HashMap<K, Vec<V>>,
+pub struct MultiMap<K, V, S = RandomState> {
+     data: HashMap<K, Vec<V>, S>,
+}
+
+/// A view into a single key's slot in a `MultiMap`, obtained by calling
+/// [`MultiMap::entry`].
+///
+/// This enum mirrors `std::collections::hash_map::Entry`, but the occupied
+/// variant holds the whole value bucket rather than a single value, to
+/// match the multi-value semantics of `MultiMap`.
+pub enum Entry<'a, K: 'a, V: 'a> {
+    /// An occupied entry, holding the key's existing bucket of values.
+    Occupied(OccupiedEntry<'a, K, V>),
+    /// A vacant entry, where the key has no bucket yet.
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+/// An occupied entry, wrapping the bucket of values for an existing key.
+pub struct OccupiedEntry<'a, K: 'a, V: 'a> {
+    inner: hash_map::OccupiedEntry<'a, K, Vec<V>>,
+}
+
+/// A vacant entry, ready to be filled with a bucket of values.
+pub struct VacantEntry<'a, K: 'a, V: 'a> {
+    inner: hash_map::VacantEntry<'a, K, Vec<V>>,
+}
+
+impl<'a, K, V> Entry<'a, K, V> {
+    /// Ensures the key has a (possibly empty) bucket, returning a mutable
+    /// reference to it.
+    pub fn or_insert_empty(self) -> &'a mut Vec<V> {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert_empty(),
+        }
+    }
+
+    /// Appends `v` to the key's bucket, creating a one-element bucket if the
+    /// key was not already present.
+    pub fn push(self, v: V) {
+        match self {
+            Entry::Occupied(mut entry) => entry.push(v),
+            Entry::Vacant(entry) => { entry.insert_empty().push(v); }
+        }
+    }
+
+    /// If the key is present, calls `f` with a mutable reference to its
+    /// bucket. Returns `self` unchanged so further entry methods can be
+    /// chained, e.g. `entry.and_modify(|v| v.push(1)).or_insert_empty()`.
+    pub fn and_modify<F: FnOnce(&mut Vec<V>)>(self, f: F) -> Entry<'a, K, V> {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+    /// Returns a mutable reference to the key's bucket of values.
+    pub fn get_mut(&mut self) -> &mut Vec<V> {
+        self.inner.get_mut()
+    }
+
+    /// Converts the entry into a mutable reference to the key's bucket,
+    /// tied to the lifetime of the map.
+    pub fn into_mut(self) -> &'a mut Vec<V> {
+        self.inner.into_mut()
+    }
+
+    /// Appends `v` to the bucket.
+    pub fn push(&mut self, v: V) {
+        self.get_mut().push(v);
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V> {
+    /// Inserts an empty bucket for the key and returns a mutable reference
+    /// to it.
+    pub fn insert_empty(self) -> &'a mut Vec<V> {
+        self.inner.insert(Vec::new())
+    }
 }
 
-impl<K: Hash + Eq, V> Collection for MultiMap<K, V> {
-    fn len(&self) -> uint {
+impl<K: Hash + Eq, V, S: BuildHasher> MultiMap<K, V, S> {
+    /// Returns the number of keys in the map.
+    pub fn len(&self) -> usize {
         self.data.len()
     }
 
-    fn is_empty(&self) -> bool {
+    /// Returns `true` if the map contains no keys.
+    pub fn is_empty(&self) -> bool {
         self.data.is_empty()
     }
-}
 
-impl<K: Hash + Eq, V> Mutable for MultiMap<K, V> {
-    fn clear(&mut self) {
+    /// Removes all keys and values from the map.
+    pub fn clear(&mut self) {
         self.data.clear();
     }
 }
 
-impl<K: Hash + Eq, V: PartialEq> PartialEq for MultiMap<K, V> {
-    fn eq(&self, other: &MultiMap<K, V>) -> bool {
+impl<K: Hash + Eq, V: PartialEq, S: BuildHasher> PartialEq for MultiMap<K, V, S> {
+    fn eq(&self, other: &MultiMap<K, V, S>) -> bool {
         self.data.eq(&other.data)
     }
 }
 
-impl<K: Hash + Eq + Show, V: Show> Show for MultiMap<K, V> {
+impl<K: Hash + Eq + fmt::Debug, V: fmt::Debug, S: BuildHasher> fmt::Debug for MultiMap<K, V, S> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.data.fmt(f)
     }
 }
 
-impl<K: Clone + Eq + Hash, V: Clone + PartialEq> FromIterator<(K, V)> for MultiMap<K, V> {
-    fn from_iter<I: Iterator<(K, V)>>(mut iter: I) -> MultiMap<K, V> {
+impl<K: Eq + Hash, V> FromIterator<(K, V)> for MultiMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> MultiMap<K, V> {
+        let iter = iter.into_iter();
         let (lower, _) = iter.size_hint();
-        let mut map: MultiMap<K, V> = MultiMap::with_capacity_and_default_hasher(lower);
+        let mut map: MultiMap<K, V> = MultiMap::with_capacity_and_hasher(lower, RandomState::default());
         for (k, v) in iter {
             map.insert(k, v);
         }
@@ -70,35 +161,57 @@ impl<K: Clone + Eq + Hash, V: Clone + PartialEq> FromIterator<(K, V)> for MultiM
     }
 }
 
-impl<K: Clone + Eq + Hash, V: Clone + PartialEq> MultiMap<K, V> {
-    /// Constructs a new `MultiMap` with a default hasher and a specified 
-    /// initial size.
-    ///
-    /// Currently, this is not public because it is to only be used by the 
-    /// `FromIterator` implementation for `MultiMap`.
-    fn with_capacity_and_default_hasher(capacity: uint) -> MultiMap<K, V> {
-        MultiMap {
-            data: HashMap::with_capacity_and_hasher(capacity, Default::default())
-        }
+impl<K: Eq + Hash, V> Default for MultiMap<K, V> {
+    fn default() -> MultiMap<K, V> {
+        MultiMap::new()
     }
+}
 
+impl<K: Eq + Hash, V> MultiMap<K, V> {
     /// Construct a new `MultiMap`.
     pub fn new() -> MultiMap<K, V> {
         MultiMap {
             data: HashMap::new()
         }
     }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> MultiMap<K, V, S> {
+    /// Constructs a new `MultiMap` that will use the given hash builder
+    /// to hash keys.
+    pub fn with_hasher(hash_builder: S) -> MultiMap<K, V, S> {
+        MultiMap {
+            data: HashMap::with_hasher(hash_builder)
+        }
+    }
+
+    /// Constructs a new `MultiMap` with the given initial capacity, using
+    /// the given hash builder to hash keys.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> MultiMap<K, V, S> {
+        MultiMap {
+            data: HashMap::with_capacity_and_hasher(capacity, hash_builder)
+        }
+    }
+
+    /// Gets the given key's corresponding entry in the map for in-place
+    /// manipulation.
+    pub fn entry(&mut self, k: K) -> Entry<'_, K, V> {
+        match self.data.entry(k) {
+            hash_map::Entry::Occupied(entry) => Entry::Occupied(OccupiedEntry { inner: entry }),
+            hash_map::Entry::Vacant(entry) => Entry::Vacant(VacantEntry { inner: entry }),
+        }
+    }
 
-    /// Retrieves a vector of values for the given key, failing if the 
+    /// Retrieves a vector of values for the given key, failing if the
     /// key is not present.
-    pub fn get<'a>(&'a self, k: &K) -> &'a Vec<V> {
-        self.data.get(k)
+    pub fn get(&self, k: &K) -> &Vec<V> {
+        self.data.get(k).expect("key not found in MultiMap")
     }
 
-    /// Retrieves a (mutable) vector of values for the given key, failing if the 
+    /// Retrieves a (mutable) vector of values for the given key, failing if the
     /// key is not present.
-    pub fn get_mut<'a>(&'a mut self, k: &K) -> &'a mut Vec<V> {
-        self.data.get_mut(k)
+    pub fn get_mut(&mut self, k: &K) -> &mut Vec<V> {
+        self.data.get_mut(k).expect("key not found in MultiMap")
     }
 
     /// Return true if the map contains a value for a specified key.
@@ -106,89 +219,152 @@ impl<K: Clone + Eq + Hash, V: Clone + PartialEq> MultiMap<K, V> {
         self.data.contains_key(k)
     }
 
-    /// WARNING: hack
-    /// An iterator visiting all key-value pairs in arbitrary order
-    /// Iterator element type is (K, V>).
-    /// TODO(talevy): figure out a clean way to lazily iterate
-    pub fn as_vec<'a>(&'a self) -> Vec<(K, V)> {
-        let mut entries: Vec<(K, V)> = Vec::new();
+    /// An iterator visiting all key-bucket pairs in arbitrary order.
+    /// Iterator element type is `(&K, &Vec<V>)`.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { inner: self.data.iter() }
+    }
 
-        for (_k, v) in self.data.iter() {
-            let rep = Repeat::new(_k);
-            for (kk, vv) in rep.zip(v.iter()) {
-                entries.push((kk.clone(), vv.clone()));
-            }
-        }
-        entries
+    /// An iterator visiting all key-bucket pairs in arbitrary order, with
+    /// mutable access to each bucket. Iterator element type is
+    /// `(&K, &mut Vec<V>)`.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut { inner: self.data.iter_mut() }
+    }
+
+    /// An iterator visiting all keys in arbitrary order.
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys { inner: self.data.keys() }
+    }
+
+    /// An iterator visiting all value buckets in arbitrary order.
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values { inner: self.data.values() }
+    }
+
+    /// An iterator visiting one `(&K, &V)` pair per stored key-value pair,
+    /// lazily flattening each key's bucket. Replaces the old `as_vec` hack.
+    pub fn iter_flat(&self) -> IterFlat<'_, K, V> {
+        IterFlat { inner: self.data.iter(), current: None }
+    }
+
+    /// Clears the map, returning all key-bucket pairs as an iterator.
+    /// Keeps the allocated memory for reuse.
+    pub fn drain(&mut self) -> Drain<'_, K, V> {
+        Drain { inner: self.data.drain() }
     }
 
     /// Inserts the specified key-value pair into the multimap.
     ///
-    /// Duplicate key-value pairs are allowed. If (k,v) is already found 
+    /// Duplicate key-value pairs are allowed. If (k,v) is already found
     /// in the map, another will be added.
     #[inline]
     pub fn insert(&mut self, k: K, v: V) {
-        if self.data.contains_key(&k) {
-            self.data.get_mut(&k).push(v);
-        } else {
-            self.data.insert(k, vec!(v));
-        }
+        self.entry(k).push(v);
     }
 
     /// Removes key and it's associated value from map.
     #[inline]
     pub fn remove(&mut self, k: &K) -> bool {
-        self.data.remove(k)
+        self.data.remove(k).is_some()
     }
 
     /// Removes specified key-value pair from map.
     /// If no more values associated with specified key,
     /// that key will be removed from map.
     #[inline]
-    pub fn remove_value(&mut self, k: &K, v: &V) -> bool {
-        if self.data.contains_key(k) {
-            let mut new_vec: Vec<V> = Vec::new();
-            for val in self.get(k).iter() {
-                if val != v {
-                    new_vec.push(val.clone());
-                }
-            }
-            if new_vec.is_empty() {
-                self.remove(k);
-            } else {
-                *self.data.get_mut(k) = new_vec;
+    pub fn remove_value(&mut self, k: &K, v: &V) -> bool where V: PartialEq {
+        if !self.data.contains_key(k) {
+            return false;
+        }
+
+        let empty = {
+            let bucket = self.data.get_mut(k).unwrap();
+            bucket.retain(|val| val != v);
+            bucket.is_empty()
+        };
+
+        if empty {
+            self.remove(k);
+        }
+        true
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> IntoIterator for MultiMap<K, V, S> {
+    type Item = (K, Vec<V>);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        IntoIter { inner: self.data.into_iter() }
+    }
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher> IntoIterator for &'a MultiMap<K, V, S> {
+    type Item = (&'a K, &'a Vec<V>);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher> IntoIterator for &'a mut MultiMap<K, V, S> {
+    type Item = (&'a K, &'a mut Vec<V>);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> IterMut<'a, K, V> {
+        self.iter_mut()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    use std::hash::Hasher;
+
+    /// A minimal FNV-1a `Hasher`, standing in for a real alternate
+    /// `BuildHasher` so tests can exercise `S != RandomState`.
+    #[derive(Default)]
+    pub(crate) struct FnvHasher(u64);
+
+    impl Hasher for FnvHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            const PRIME: u64 = 0x100000001b3;
+            let mut hash = if self.0 == 0 { 0xcbf29ce484222325 } else { self.0 };
+            for &b in bytes {
+                hash ^= b as u64;
+                hash = hash.wrapping_mul(PRIME);
             }
-            true
-        } else {
-            false
+            self.0 = hash;
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    extern crate test;
-
+    use super::test_support::FnvHasher;
     use super::MultiMap;
+    use std::hash::BuildHasherDefault;
 
-    type TestMultiMap = MultiMap<uint, uint>;
+    type TestMultiMap = MultiMap<usize, usize>;
 
     fn create_test_map() -> TestMultiMap {
-        let mut map: MultiMap<uint, uint> = MultiMap::new();
+        let mut map: MultiMap<usize, usize> = MultiMap::new();
         map.insert(1, 3);
         map.insert(1, 5);
         map.insert(1, 7);
-        return map;
+        map
     }
 
     #[test]
     fn test_order() {
         let map = create_test_map();
-        let vec = map.as_vec();
-        let mut iter = vec.iter();
-        assert_eq!(iter.next().unwrap(), &(1u, 3u));
-        assert_eq!(iter.next().unwrap(), &(1u, 5u));
-        assert_eq!(iter.next().unwrap(), &(1u, 7u));
+        let vec: Vec<(usize, usize)> = map.iter_flat().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(vec, vec![(1, 3), (1, 5), (1, 7)]);
     }
 
     #[test]
@@ -219,12 +395,80 @@ mod tests {
     #[test]
     fn test_from_iterator() {
         // sorted input tuples
-        let input = vec!((1u, 3u), (1u, 5u), (2u, 6u));
-        let map: MultiMap<uint, uint> = input.iter().map(|x| *x).collect();
-        let mut test = map.as_vec();
+        let input = vec![(1, 3), (1, 5), (2, 6)];
+        let map: MultiMap<usize, usize> = input.iter().cloned().collect();
+        let mut test: Vec<(usize, usize)> = map.iter_flat().map(|(&k, &v)| (k, v)).collect();
 
         test.sort();
 
         assert_eq!(input, test);
     }
+
+    #[test]
+    fn test_entry_or_insert_empty() {
+        let mut map: MultiMap<usize, usize> = MultiMap::new();
+        map.entry(1).or_insert_empty().push(10);
+        assert_eq!(map.get(&1), &vec![10]);
+    }
+
+    #[test]
+    fn test_entry_push() {
+        let mut map = create_test_map();
+        map.entry(1).push(9);
+        map.entry(2).push(1);
+        assert_eq!(map.get(&1), &vec![3, 5, 7, 9]);
+        assert_eq!(map.get(&2), &vec![1]);
+    }
+
+    #[test]
+    fn test_entry_and_modify() {
+        let mut map = create_test_map();
+        map.entry(1).and_modify(|vec| vec.push(11));
+        map.entry(2).and_modify(|vec| vec.push(11));
+        assert_eq!(map.get(&1), &vec![3, 5, 7, 11]);
+        assert!(!map.contains_key(&2));
+    }
+
+    #[test]
+    fn test_iter_flat() {
+        let map = create_test_map();
+        let mut pairs: Vec<(usize, usize)> = map.iter_flat().map(|(&k, &v)| (k, v)).collect();
+        pairs.sort();
+        assert_eq!(pairs, vec![(1, 3), (1, 5), (1, 7)]);
+    }
+
+    #[test]
+    fn test_keys_and_values() {
+        let mut map = create_test_map();
+        map.insert(2, 6);
+        let mut keys: Vec<&usize> = map.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec![&1, &2]);
+        assert_eq!(map.values().count(), 2);
+    }
+
+    #[test]
+    fn test_into_iterator() {
+        let map = create_test_map();
+        let mut buckets: Vec<(usize, Vec<usize>)> = map.into_iter().collect();
+        buckets.sort();
+        assert_eq!(buckets, vec![(1, vec![3, 5, 7])]);
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut map = create_test_map();
+        let drained: Vec<(usize, Vec<usize>)> = map.drain().collect();
+        assert_eq!(drained, vec![(1, vec![3, 5, 7])]);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_alternate_build_hasher() {
+        let mut map: MultiMap<usize, usize, BuildHasherDefault<FnvHasher>> =
+            MultiMap::with_hasher(BuildHasherDefault::default());
+        map.insert(1, 3);
+        map.entry(1).push(5);
+        assert_eq!(map.get(&1), &vec![3, 5]);
+    }
 }
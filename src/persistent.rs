@@ -0,0 +1,379 @@
+//! A persistent, structurally-shared `MultiMap` variant backed by a hash
+//! array mapped trie (HAMT), for use cases that need to keep many
+//! historical versions around cheaply (undo stacks, concurrent readers,
+//! snapshotting).
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+const BITS: u32 = 5;
+const ARITY: u32 = 1 << BITS;
+const MASK: u64 = (ARITY - 1) as u64;
+
+fn slot(hash: u64, shift: u32) -> u32 {
+    ((hash >> shift) & MASK) as u32
+}
+
+enum Node<K, V> {
+    Empty,
+    Leaf { hash: u64, key: K, values: Rc<Vec<V>> },
+    /// Keys whose hashes agree in every chunk consumed so far (a true hash
+    /// collision, or the trie's max depth reached).
+    Collision { hash: u64, entries: Vec<(K, Rc<Vec<V>>)> },
+    /// A bitmap-compressed interior node: `bitmap` has a set bit for each
+    /// occupied child slot, and `children` holds only the occupied slots
+    /// in order, so branches with few children don't waste space on a
+    /// full 32-wide array.
+    Branch { bitmap: u32, children: Vec<Rc<Node<K, V>>> },
+}
+
+/// An immutable multimap backed by a hash array mapped trie.
+///
+/// `insert` and `remove_value` do not mutate the map in place; they
+/// return a *new* `PersistentMultiMap` that shares every untouched
+/// subtree with the original via reference-counted nodes. This makes
+/// `clone` O(1) (it just bumps the root's refcount) and an update
+/// O(log₃₂ n), while every prior version of the map remains valid and
+/// unaffected. Keys map to a bucket of values, same as `MultiMap`.
+pub struct PersistentMultiMap<K, V> {
+    root: Rc<Node<K, V>>,
+    len: usize,
+}
+
+impl<K, V> Clone for PersistentMultiMap<K, V> {
+    fn clone(&self) -> Self {
+        PersistentMultiMap { root: self.root.clone(), len: self.len }
+    }
+}
+
+impl<K, V> Default for PersistentMultiMap<K, V> {
+    fn default() -> PersistentMultiMap<K, V> {
+        PersistentMultiMap::new()
+    }
+}
+
+impl<K, V> PersistentMultiMap<K, V> {
+    /// Construct a new, empty `PersistentMultiMap`.
+    pub fn new() -> PersistentMultiMap<K, V> {
+        PersistentMultiMap { root: Rc::new(Node::Empty), len: 0 }
+    }
+
+    /// Returns the number of keys in the map.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the map contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> PersistentMultiMap<K, V> {
+    fn hash_key(k: &K) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        k.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Retrieves the bucket of values for the given key, if present.
+    pub fn get(&self, k: &K) -> Option<&Vec<V>> {
+        Self::get_node(&self.root, Self::hash_key(k), 0, k)
+    }
+
+    /// Return true if the map contains a value for a specified key.
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.get(k).is_some()
+    }
+
+    fn get_node<'a>(node: &'a Rc<Node<K, V>>, hash: u64, shift: u32, k: &K) -> Option<&'a Vec<V>> {
+        match &**node {
+            Node::Empty => None,
+            Node::Leaf { hash: lh, key: lk, values } => {
+                if *lh == hash && lk == k { Some(values) } else { None }
+            }
+            Node::Collision { hash: ch, entries } => {
+                if *ch != hash {
+                    return None;
+                }
+                entries.iter().find(|(ek, _)| ek == k).map(|(_, v)| &**v)
+            }
+            Node::Branch { bitmap, children } => {
+                let bit = 1u32 << slot(hash, shift);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                let pos = (bitmap & (bit - 1)).count_ones() as usize;
+                Self::get_node(&children[pos], hash, shift + BITS, k)
+            }
+        }
+    }
+
+    /// Returns a new map with `v` appended to `k`'s bucket (creating a
+    /// one-element bucket if `k` was not already present), sharing every
+    /// subtree untouched by the insertion with `self`.
+    pub fn insert(&self, k: K, v: V) -> PersistentMultiMap<K, V> {
+        let hash = Self::hash_key(&k);
+        let (new_root, is_new_key) = Self::insert_node(&self.root, hash, 0, k, v);
+        PersistentMultiMap { root: new_root, len: self.len + if is_new_key { 1 } else { 0 } }
+    }
+
+    fn insert_node(
+        node: &Rc<Node<K, V>>,
+        hash: u64,
+        shift: u32,
+        k: K,
+        v: V,
+    ) -> (Rc<Node<K, V>>, bool) {
+        match &**node {
+            Node::Empty => {
+                (Rc::new(Node::Leaf { hash, key: k, values: Rc::new(vec![v]) }), true)
+            }
+            Node::Leaf { hash: lh, key: lk, values } => {
+                if *lh == hash && *lk == k {
+                    let mut new_values = (**values).clone();
+                    new_values.push(v);
+                    (Rc::new(Node::Leaf { hash, key: k, values: Rc::new(new_values) }), false)
+                } else if *lh == hash {
+                    let entries = vec![(lk.clone(), values.clone()), (k, Rc::new(vec![v]))];
+                    (Rc::new(Node::Collision { hash, entries }), true)
+                } else {
+                    (Self::merge(shift, *lh, node.clone(), hash, k, v), true)
+                }
+            }
+            Node::Collision { hash: ch, entries } => {
+                if *ch == hash {
+                    let mut new_entries = entries.clone();
+                    match new_entries.iter().position(|(ek, _)| *ek == k) {
+                        Some(idx) => {
+                            let mut new_values = (*new_entries[idx].1).clone();
+                            new_values.push(v);
+                            new_entries[idx].1 = Rc::new(new_values);
+                            (Rc::new(Node::Collision { hash, entries: new_entries }), false)
+                        }
+                        None => {
+                            new_entries.push((k, Rc::new(vec![v])));
+                            (Rc::new(Node::Collision { hash, entries: new_entries }), true)
+                        }
+                    }
+                } else {
+                    (Self::merge(shift, *ch, node.clone(), hash, k, v), true)
+                }
+            }
+            Node::Branch { bitmap, children } => {
+                let bit = 1u32 << slot(hash, shift);
+                let pos = (bitmap & (bit - 1)).count_ones() as usize;
+                if bitmap & bit != 0 {
+                    let (new_child, is_new_key) =
+                        Self::insert_node(&children[pos], hash, shift + BITS, k, v);
+                    let mut new_children = children.clone();
+                    new_children[pos] = new_child;
+                    (Rc::new(Node::Branch { bitmap: *bitmap, children: new_children }), is_new_key)
+                } else {
+                    let mut new_children = children.clone();
+                    let leaf = Rc::new(Node::Leaf { hash, key: k, values: Rc::new(vec![v]) });
+                    new_children.insert(pos, leaf);
+                    (Rc::new(Node::Branch { bitmap: bitmap | bit, children: new_children }), true)
+                }
+            }
+        }
+    }
+
+    /// Builds the branch(es) needed to hold `existing` (occupying its own
+    /// slot at `shift`) alongside a brand-new `(new_key, new_val)` leaf,
+    /// recursing one trie level deeper each time their hashes still agree
+    /// in the current 5-bit chunk.
+    fn merge(
+        shift: u32,
+        existing_hash: u64,
+        existing: Rc<Node<K, V>>,
+        new_hash: u64,
+        new_key: K,
+        new_val: V,
+    ) -> Rc<Node<K, V>> {
+        if shift >= 64 {
+            // Every chunk of both 64-bit hashes has agreed; this can only
+            // happen on a genuine hash collision between distinct keys.
+            let mut entries = Self::drain_entries(existing);
+            entries.push((new_key, Rc::new(vec![new_val])));
+            return Rc::new(Node::Collision { hash: new_hash, entries });
+        }
+
+        let existing_slot = slot(existing_hash, shift);
+        let new_slot = slot(new_hash, shift);
+
+        if existing_slot != new_slot {
+            let new_leaf = Rc::new(Node::Leaf { hash: new_hash, key: new_key, values: Rc::new(vec![new_val]) });
+            let bitmap = (1 << existing_slot) | (1 << new_slot);
+            let children = if existing_slot < new_slot {
+                vec![existing, new_leaf]
+            } else {
+                vec![new_leaf, existing]
+            };
+            Rc::new(Node::Branch { bitmap, children })
+        } else {
+            let child = Self::merge(shift + BITS, existing_hash, existing, new_hash, new_key, new_val);
+            Rc::new(Node::Branch { bitmap: 1 << existing_slot, children: vec![child] })
+        }
+    }
+
+    fn drain_entries(node: Rc<Node<K, V>>) -> Vec<(K, Rc<Vec<V>>)> {
+        match Rc::try_unwrap(node) {
+            Ok(Node::Leaf { key, values, .. }) => vec![(key, values)],
+            Ok(Node::Collision { entries, .. }) => entries,
+            Ok(_) => unreachable!("merge only ever wraps a Leaf or Collision node"),
+            Err(node) => match &*node {
+                Node::Leaf { key, values, .. } => vec![(key.clone(), values.clone())],
+                Node::Collision { entries, .. } => entries.clone(),
+                _ => unreachable!("merge only ever wraps a Leaf or Collision node"),
+            },
+        }
+    }
+
+    /// Returns a new map with `v` removed from `k`'s bucket. If the bucket
+    /// becomes empty, `k` is removed from the map entirely. Subtrees
+    /// untouched by the removal are shared with `self`.
+    pub fn remove_value(&self, k: &K, v: &V) -> PersistentMultiMap<K, V> where V: PartialEq {
+        let hash = Self::hash_key(k);
+        let new_root = Self::remove_node(&self.root, hash, 0, k, v)
+            .unwrap_or_else(|| Rc::new(Node::Empty));
+        let had_key = self.contains_key(k);
+        let has_key = Self::get_node(&new_root, hash, 0, k).is_some();
+        let new_len = if had_key && !has_key { self.len - 1 } else { self.len };
+        PersistentMultiMap { root: new_root, len: new_len }
+    }
+
+    fn remove_node(
+        node: &Rc<Node<K, V>>,
+        hash: u64,
+        shift: u32,
+        k: &K,
+        v: &V,
+    ) -> Option<Rc<Node<K, V>>> where V: PartialEq {
+        match &**node {
+            Node::Empty => Some(node.clone()),
+            Node::Leaf { hash: lh, key: lk, values } => {
+                if *lh != hash || lk != k {
+                    return Some(node.clone());
+                }
+                let new_values: Vec<V> = values.iter().filter(|val| *val != v).cloned().collect();
+                if new_values.is_empty() {
+                    None
+                } else {
+                    Some(Rc::new(Node::Leaf { hash, key: lk.clone(), values: Rc::new(new_values) }))
+                }
+            }
+            Node::Collision { hash: ch, entries } => {
+                if *ch != hash {
+                    return Some(node.clone());
+                }
+                let idx = match entries.iter().position(|(ek, _)| ek == k) {
+                    Some(idx) => idx,
+                    None => return Some(node.clone()),
+                };
+                let new_values: Vec<V> = entries[idx].1.iter().filter(|val| *val != v).cloned().collect();
+                let mut new_entries = entries.clone();
+                if new_values.is_empty() {
+                    new_entries.remove(idx);
+                } else {
+                    new_entries[idx].1 = Rc::new(new_values);
+                }
+                match new_entries.len() {
+                    0 => None,
+                    1 => {
+                        let (key, values) = new_entries.into_iter().next().unwrap();
+                        Some(Rc::new(Node::Leaf { hash, key, values }))
+                    }
+                    _ => Some(Rc::new(Node::Collision { hash, entries: new_entries })),
+                }
+            }
+            Node::Branch { bitmap, children } => {
+                let bit = 1u32 << slot(hash, shift);
+                if bitmap & bit == 0 {
+                    return Some(node.clone());
+                }
+                let pos = (bitmap & (bit - 1)).count_ones() as usize;
+                match Self::remove_node(&children[pos], hash, shift + BITS, k, v) {
+                    Some(new_child) => {
+                        let mut new_children = children.clone();
+                        new_children[pos] = new_child;
+                        Some(Rc::new(Node::Branch { bitmap: *bitmap, children: new_children }))
+                    }
+                    None => {
+                        let new_bitmap = bitmap & !bit;
+                        if new_bitmap == 0 {
+                            None
+                        } else {
+                            let mut new_children = children.clone();
+                            new_children.remove(pos);
+                            Some(Rc::new(Node::Branch { bitmap: new_bitmap, children: new_children }))
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PersistentMultiMap;
+
+    fn create_test_map() -> PersistentMultiMap<usize, usize> {
+        PersistentMultiMap::new().insert(1, 3).insert(1, 5).insert(1, 7)
+    }
+
+    #[test]
+    fn test_insert_and_get() {
+        let map = create_test_map();
+        assert_eq!(map.get(&1), Some(&vec![3, 5, 7]));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn test_insert_shares_untouched_snapshot() {
+        let v1 = create_test_map();
+        let v2 = v1.insert(2, 9);
+
+        assert_eq!(v1.get(&2), None);
+        assert_eq!(v2.get(&1), Some(&vec![3, 5, 7]));
+        assert_eq!(v2.get(&2), Some(&vec![9]));
+        assert_eq!(v1.len(), 1);
+        assert_eq!(v2.len(), 2);
+    }
+
+    #[test]
+    fn test_clone_is_cheap_and_independent() {
+        let v1 = create_test_map();
+        let v2 = v1.clone();
+        let v3 = v2.insert(1, 9);
+
+        assert_eq!(v1.get(&1), Some(&vec![3, 5, 7]));
+        assert_eq!(v3.get(&1), Some(&vec![3, 5, 7, 9]));
+    }
+
+    #[test]
+    fn test_remove_value() {
+        let map = create_test_map();
+        let map = map.remove_value(&1, &5);
+        assert_eq!(map.get(&1), Some(&vec![3, 7]));
+
+        let map = map.remove_value(&1, &3).remove_value(&1, &7);
+        assert_eq!(map.get(&1), None);
+        assert!(!map.contains_key(&1));
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    fn test_many_keys_round_trip() {
+        let mut map = PersistentMultiMap::new();
+        for i in 0..200usize {
+            map = map.insert(i, i * 2);
+        }
+        for i in 0..200usize {
+            assert_eq!(map.get(&i), Some(&vec![i * 2]));
+        }
+        assert_eq!(map.len(), 200);
+    }
+}